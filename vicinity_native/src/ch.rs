@@ -0,0 +1,1129 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use fast_paths::{self, FastGraph32, InputGraph};
+use numpy::{PyArray1, PyArray2, PyArrayMethods, PyReadonlyArray1};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use rayon::prelude::*;
+use rstar::{PointDistance, RTree, RTreeObject, AABB};
+use rustc_hash::FxHashMap;
+use sha3::{Digest, Sha3_256};
+
+/// Precomputed restricted search space for RPHAST: the union of the
+/// upward-reachable nodes of a fixed target set, plus the down-edges among
+/// just those nodes, so repeated `query_restricted` calls against the same
+/// targets skip the full downward sweep over every node in the graph.
+#[pyclass(module = "t_hex")]
+pub struct RestrictedTargets {
+    target_ids: Vec<i32>,
+    // Restricted node ids, sorted by rank descending (the sweep order).
+    restricted_nodes: Vec<u32>,
+    // CSR over `restricted_nodes`: sub_offsets[slot]..sub_offsets[slot+1]
+    // indexes into sub_edges, giving (base_node_id, weight) pairs.
+    sub_offsets: Vec<usize>,
+    sub_edges: Vec<(u32, u32)>,
+}
+
+#[pymethods]
+impl RestrictedTargets {
+    #[getter]
+    fn num_targets(&self) -> usize {
+        self.target_ids.len()
+    }
+
+    #[getter]
+    fn num_restricted_nodes(&self) -> usize {
+        self.restricted_nodes.len()
+    }
+}
+
+const INF_U32: u32 = u32::MAX;
+
+// On-disk container: `CH_FILE_MAGIC` + u32 version (LE), followed by the
+// bincode-serialized `FastGraph32`, the precomputed `order`/`down_offsets`/
+// `down_edges`/`down_edge_bwd_idx`/`up_offsets`/`up_edges`/`up_edge_bwd_idx`
+// arrays as POD little-endian slices, and a trailing SHA3-256 digest of
+// everything before it.
+const CH_FILE_MAGIC: &[u8; 8] = b"CHGRAPH1";
+const CH_FILE_VERSION: u32 = 2;
+const CH_DIGEST_LEN: usize = 32;
+
+#[pyclass(module = "t_hex")]
+pub struct CHGraph {
+    graph: FastGraph32,
+    order: Vec<u32>,
+    down_offsets: Vec<usize>,
+    down_edges: Vec<(usize, u32)>,
+    // Parallel to `down_edges`: the index into `graph.edges_bwd` each entry came
+    // from. Saved and loaded with the graph; no query reads it.
+    down_edge_bwd_idx: Vec<u32>,
+    // `graph.edges_bwd` grouped by `base_node` instead of `adj_node` — the
+    // mirror of `down_offsets`/`down_edges`, letting a backward search ascend
+    // from a node toward higher-rank nodes the way `run_upward_fwd_with_pred`
+    // ascends from `source`, so `dist_bwd[v]` lands on the true `v -> target`
+    // cost instead of the forward `target -> v` cost `down_edges` gives.
+    up_offsets: Vec<usize>,
+    up_edges: Vec<(usize, u32)>,
+    // Parallel to `up_edges`: the index into `graph.edges_bwd` each entry came
+    // from, kept so `query_path` can recursively unpack shortcuts on the
+    // backward half of the path.
+    up_edge_bwd_idx: Vec<u32>,
+    // Present when built with node coordinates; backs `snap()`.
+    rtree: Option<RTree<NodePoint>>,
+    // The uncontracted CSR as passed to `ch_build_from_csr`, retained only so
+    // `query_all_constrained` can run a two-criteria label-setting Dijkstra —
+    // CH shortcuts aren't valid once a second, independent cost is involved.
+    // Empty when the graph wasn't built with a secondary metric.
+    csr_indptr: Vec<i64>,
+    csr_indices: Vec<i32>,
+    csr_w_primary: Vec<u16>,
+    csr_w_secondary: Vec<u16>,
+}
+
+#[derive(Clone, Copy)]
+struct NodePoint {
+    id: u32,
+    x: f32,
+    y: f32,
+}
+
+impl RTreeObject for NodePoint {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point([self.x, self.y])
+    }
+}
+
+impl PointDistance for NodePoint {
+    fn distance_2(&self, point: &[f32; 2]) -> f32 {
+        let dx = self.x - point[0];
+        let dy = self.y - point[1];
+        dx * dx + dy * dy
+    }
+}
+
+impl CHGraph {
+    fn from_fast_graph32(graph: FastGraph32) -> Self {
+        let num_nodes = graph.ranks.len();
+        let mut order = vec![0u32; num_nodes];
+        for (node, &rank) in graph.ranks.iter().enumerate() {
+            let r = rank as usize;
+            if r < num_nodes {
+                order[r] = node as u32;
+            }
+        }
+        let mut counts = vec![0usize; num_nodes];
+        for edge in &graph.edges_bwd {
+            let adj = edge.adj_node as usize;
+            if adj < num_nodes {
+                counts[adj] += 1;
+            }
+        }
+        let mut down_offsets = vec![0usize; num_nodes + 1];
+        for i in 0..num_nodes {
+            down_offsets[i + 1] = down_offsets[i] + counts[i];
+        }
+        let mut down_edges = vec![(0usize, 0u32); down_offsets[num_nodes]];
+        let mut down_edge_bwd_idx = vec![0u32; down_offsets[num_nodes]];
+        let mut cursor = down_offsets.clone();
+        for (bwd_idx, edge) in graph.edges_bwd.iter().enumerate() {
+            let adj = edge.adj_node as usize;
+            let base = edge.base_node as usize;
+            if adj >= num_nodes || base >= num_nodes {
+                continue;
+            }
+            let pos = cursor[adj];
+            down_edges[pos] = (base, edge.weight as u32);
+            down_edge_bwd_idx[pos] = bwd_idx as u32;
+            cursor[adj] = pos + 1;
+        }
+
+        let mut up_counts = vec![0usize; num_nodes];
+        for edge in &graph.edges_bwd {
+            let base = edge.base_node as usize;
+            if base < num_nodes {
+                up_counts[base] += 1;
+            }
+        }
+        let mut up_offsets = vec![0usize; num_nodes + 1];
+        for i in 0..num_nodes {
+            up_offsets[i + 1] = up_offsets[i] + up_counts[i];
+        }
+        let mut up_edges = vec![(0usize, 0u32); up_offsets[num_nodes]];
+        let mut up_edge_bwd_idx = vec![0u32; up_offsets[num_nodes]];
+        let mut up_cursor = up_offsets.clone();
+        for (bwd_idx, edge) in graph.edges_bwd.iter().enumerate() {
+            let adj = edge.adj_node as usize;
+            let base = edge.base_node as usize;
+            if adj >= num_nodes || base >= num_nodes {
+                continue;
+            }
+            let pos = up_cursor[base];
+            up_edges[pos] = (adj, edge.weight as u32);
+            up_edge_bwd_idx[pos] = bwd_idx as u32;
+            up_cursor[base] = pos + 1;
+        }
+
+        Self {
+            graph,
+            order,
+            down_offsets,
+            down_edges,
+            down_edge_bwd_idx,
+            up_offsets,
+            up_edges,
+            up_edge_bwd_idx,
+            rtree: None,
+            csr_indptr: Vec::new(),
+            csr_indices: Vec::new(),
+            csr_w_primary: Vec::new(),
+            csr_w_secondary: Vec::new(),
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        self.graph.ranks.len()
+    }
+
+    /// Build the R-tree used by `snap()` over node positions. `xs`/`ys` must
+    /// have one entry per node, indexed the same way as every other
+    /// per-node array in this crate.
+    fn build_rtree(&mut self, xs: &[f32], ys: &[f32]) -> PyResult<()> {
+        let n = self.node_count();
+        if xs.len() != n || ys.len() != n {
+            return Err(PyValueError::new_err(
+                "xs/ys must have one entry per node",
+            ));
+        }
+        let points: Vec<NodePoint> = (0..n)
+            .map(|i| NodePoint { id: i as u32, x: xs[i], y: ys[i] })
+            .collect();
+        self.rtree = Some(RTree::bulk_load(points));
+        Ok(())
+    }
+
+    /// Retain the uncontracted CSR (and an optional secondary per-edge
+    /// weight) for `query_all_constrained`, which cannot use CH shortcuts.
+    fn attach_uncontracted_csr(
+        &mut self,
+        indptr: Vec<i64>,
+        indices: Vec<i32>,
+        w_primary: Vec<u16>,
+        w_secondary: Vec<u16>,
+    ) {
+        self.csr_indptr = indptr;
+        self.csr_indices = indices;
+        self.csr_w_primary = w_primary;
+        self.csr_w_secondary = w_secondary;
+    }
+
+    /// Upward Dijkstra restricted to the up-edges, shared by `run_phast` and
+    /// `query_restricted` (RPHAST reuses the exact same upward phase).
+    fn run_upward(&self, source: usize, limit: u32) -> Vec<u32> {
+        let n = self.node_count();
+        let mut dist = vec![INF_U32; n];
+        if source >= n {
+            return dist;
+        }
+
+        let mut heap: BinaryHeap<(Reverse<u32>, usize)> = BinaryHeap::new();
+        dist[source] = 0;
+        heap.push((Reverse(0u32), source));
+
+        while let Some((Reverse(du), u)) = heap.pop() {
+            if du > limit {
+                continue;
+            }
+            if du != dist[u] {
+                continue;
+            }
+            let rank_u = self.graph.ranks[u] as usize;
+            if rank_u >= self.graph.first_edge_ids_fwd.len() - 1 {
+                continue;
+            }
+            let start = self.graph.first_edge_ids_fwd[rank_u] as usize;
+            let end = self.graph.first_edge_ids_fwd[rank_u + 1] as usize;
+            for idx in start..end {
+                let edge = &self.graph.edges_fwd[idx];
+                let v = edge.adj_node as usize;
+                let w = edge.weight as u32;
+                let nd = du.saturating_add(w);
+                if nd > limit {
+                    continue;
+                }
+                if nd < dist[v] {
+                    dist[v] = nd;
+                    heap.push((Reverse(nd), v));
+                }
+            }
+        }
+
+        dist
+    }
+
+    fn run_phast(&self, source: usize, limit: u32) -> Vec<u32> {
+        let mut dist = self.run_upward(source, limit);
+
+        // Downward sweep (PHAST)
+        for &node_u in self.order.iter().rev() {
+            let u = node_u as usize;
+            let du = dist[u];
+            if du == INF_U32 {
+                continue;
+            }
+            let start = self.down_offsets[u];
+            let end = self.down_offsets[u + 1];
+            for idx in start..end {
+                let (v, w) = self.down_edges[idx];
+                let nd = du.saturating_add(w);
+                if nd > limit {
+                    continue;
+                }
+                if nd < dist[v] {
+                    dist[v] = nd;
+                }
+            }
+        }
+
+        dist
+    }
+
+    /// Unweighted closure of `target` over `up_edges` — the same adjacency
+    /// `run_upward_bwd_with_pred` walks. Every node in this closure is exactly
+    /// a node that can reach `target` during the PHAST downward sweep (which
+    /// relaxes `down_edges`, the mirror of `up_edges`); walking `edges_fwd`
+    /// instead would give `target`'s own up-search space, not its ancestors.
+    fn mark_upward_closure(&self, target: usize, in_set: &mut [bool], stack: &mut Vec<usize>) {
+        if in_set[target] {
+            return;
+        }
+        in_set[target] = true;
+        stack.push(target);
+        while let Some(u) = stack.pop() {
+            let start = self.up_offsets[u];
+            let end = self.up_offsets[u + 1];
+            for idx in start..end {
+                let (v, _w) = self.up_edges[idx];
+                if !in_set[v] {
+                    in_set[v] = true;
+                    stack.push(v);
+                }
+            }
+        }
+    }
+
+    /// Upward Dijkstra from `source` restricted to the forward (up) edges,
+    /// recording the predecessor edge used to reach each settled node so the
+    /// caller can unpack the path afterwards.
+    fn run_upward_fwd_with_pred(&self, source: usize, limit: u32) -> (Vec<u32>, Vec<u32>) {
+        let n = self.node_count();
+        let mut dist = vec![INF_U32; n];
+        let mut pred_edge = vec![u32::MAX; n];
+        if source >= n {
+            return (dist, pred_edge);
+        }
+
+        let mut heap: BinaryHeap<(Reverse<u32>, usize)> = BinaryHeap::new();
+        dist[source] = 0;
+        heap.push((Reverse(0u32), source));
+
+        while let Some((Reverse(du), u)) = heap.pop() {
+            if du > limit || du != dist[u] {
+                continue;
+            }
+            let rank_u = self.graph.ranks[u] as usize;
+            if rank_u >= self.graph.first_edge_ids_fwd.len() - 1 {
+                continue;
+            }
+            let start = self.graph.first_edge_ids_fwd[rank_u] as usize;
+            let end = self.graph.first_edge_ids_fwd[rank_u + 1] as usize;
+            for idx in start..end {
+                let edge = &self.graph.edges_fwd[idx];
+                let v = edge.adj_node as usize;
+                let nd = du.saturating_add(edge.weight as u32);
+                if nd <= limit && nd < dist[v] {
+                    dist[v] = nd;
+                    pred_edge[v] = idx as u32;
+                    heap.push((Reverse(nd), v));
+                }
+            }
+        }
+
+        (dist, pred_edge)
+    }
+
+    /// Backward Dijkstra from `target`, ascending via `up_edges` (the mirror
+    /// of `run_upward_fwd_with_pred`'s `edges_fwd` walk, but over `edges_bwd`
+    /// grouped by `base_node`) so `dist[v]` is the true `v -> target` cost the
+    /// bidirectional meeting formula needs, not the forward `target -> v`
+    /// cost a walk over `down_edges` would give on a directed graph. Records
+    /// the predecessor edge used to reach each settled node, as an index into
+    /// `graph.edges_bwd` via `up_edge_bwd_idx`, so the caller can unpack it.
+    fn run_upward_bwd_with_pred(&self, target: usize, limit: u32) -> (Vec<u32>, Vec<u32>) {
+        let n = self.node_count();
+        let mut dist = vec![INF_U32; n];
+        let mut pred_bwd_idx = vec![u32::MAX; n];
+        if target >= n {
+            return (dist, pred_bwd_idx);
+        }
+
+        let mut heap: BinaryHeap<(Reverse<u32>, usize)> = BinaryHeap::new();
+        dist[target] = 0;
+        heap.push((Reverse(0u32), target));
+
+        while let Some((Reverse(du), u)) = heap.pop() {
+            if du > limit || du != dist[u] {
+                continue;
+            }
+            let start = self.up_offsets[u];
+            let end = self.up_offsets[u + 1];
+            for idx in start..end {
+                let (v, w) = self.up_edges[idx];
+                let nd = du.saturating_add(w);
+                if nd <= limit && nd < dist[v] {
+                    dist[v] = nd;
+                    pred_bwd_idx[v] = self.up_edge_bwd_idx[idx];
+                    heap.push((Reverse(nd), v));
+                }
+            }
+        }
+
+        (dist, pred_bwd_idx)
+    }
+
+    /// Recursively expand a CH edge (original or shortcut) into the original
+    /// node ids it passes through, pushing only the ids past `base_node` — the
+    /// caller already has `base_node` from the previous step of the walk.
+    fn unpack_edge(edges: &[fast_paths::Edge32], edge_idx: usize, out: &mut Vec<u32>) {
+        let edge = &edges[edge_idx];
+        match edge.replaced_edges {
+            Some((e1, e2)) => {
+                Self::unpack_edge(edges, e1 as usize, out);
+                Self::unpack_edge(edges, e2 as usize, out);
+            }
+            None => out.push(edge.adj_node),
+        }
+    }
+
+    /// Encode the graph plus its precomputed `order`/`down_offsets`/`down_edges`/
+    /// `up_offsets`/`up_edges` arrays as plain little-endian POD slices behind
+    /// a magic tag and version, so `ch_load_mmap` can reconstruct CSR views
+    /// without rebuilding them.
+    fn encode_payload(&self) -> PyResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(CH_FILE_MAGIC);
+        buf.extend_from_slice(&CH_FILE_VERSION.to_le_bytes());
+
+        let graph_bytes = bincode::serialize(&self.graph)
+            .map_err(|e| PyValueError::new_err(format!("failed to serialize CH graph: {e}")))?;
+        buf.extend_from_slice(&(graph_bytes.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&graph_bytes);
+
+        buf.extend_from_slice(&(self.order.len() as u64).to_le_bytes());
+        for &v in &self.order {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.down_offsets.len() as u64).to_le_bytes());
+        for &v in &self.down_offsets {
+            buf.extend_from_slice(&(v as u64).to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.down_edges.len() as u64).to_le_bytes());
+        for &(base, w) in &self.down_edges {
+            buf.extend_from_slice(&(base as u32).to_le_bytes());
+            buf.extend_from_slice(&w.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.down_edge_bwd_idx.len() as u64).to_le_bytes());
+        for &v in &self.down_edge_bwd_idx {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.up_offsets.len() as u64).to_le_bytes());
+        for &v in &self.up_offsets {
+            buf.extend_from_slice(&(v as u64).to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.up_edges.len() as u64).to_le_bytes());
+        for &(adj, w) in &self.up_edges {
+            buf.extend_from_slice(&(adj as u32).to_le_bytes());
+            buf.extend_from_slice(&w.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.up_edge_bwd_idx.len() as u64).to_le_bytes());
+        for &v in &self.up_edge_bwd_idx {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+
+        Ok(buf)
+    }
+
+    fn sha3_digest(payload: &[u8]) -> [u8; CH_DIGEST_LEN] {
+        let mut hasher = Sha3_256::new();
+        hasher.update(payload);
+        hasher.finalize().into()
+    }
+
+    fn sha3_hex(payload: &[u8]) -> String {
+        Self::sha3_digest(payload).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[pymethods]
+impl CHGraph {
+    #[getter]
+    fn num_nodes(&self) -> usize {
+        self.node_count()
+    }
+
+    fn to_bytes(&self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        match bincode::serialize(&self.graph) {
+            Ok(data) => Ok(PyBytes::new_bound(py, &data).unbind()),
+            Err(e) => Err(PyValueError::new_err(format!("failed to serialize CH graph: {e}"))),
+        }
+    }
+
+    /// SHA3-256 digest (hex) of this graph's serialized payload, so Python
+    /// caches can key on-disk artifacts and detect stale or corrupt files.
+    #[getter]
+    fn fingerprint(&self) -> PyResult<String> {
+        let payload = self.encode_payload()?;
+        Ok(Self::sha3_hex(&payload))
+    }
+
+    /// Write the versioned, mmap-friendly container read back by `ch_load_mmap`.
+    fn save(&self, path: &str) -> PyResult<()> {
+        let mut payload = self.encode_payload()?;
+        let digest = Self::sha3_digest(&payload);
+        payload.extend_from_slice(&digest);
+        std::fs::write(path, &payload)
+            .map_err(|e| PyValueError::new_err(format!("failed to write {}: {}", path, e)))
+    }
+
+    /// Map each `(xs[i], ys[i])` to the nearest node id, or -1 if none falls
+    /// within `max_dist`. Returns `(node_ids, dist_sq)`, vectorized over the
+    /// query arrays and run with the GIL released. Requires the graph to have
+    /// been built with coordinates (`ch_build_from_csr(..., xs=..., ys=...)`).
+    #[pyo3(signature = (xs, ys, max_dist=None))]
+    fn snap(
+        &self,
+        py: Python<'_>,
+        xs: PyReadonlyArray1<f32>,
+        ys: PyReadonlyArray1<f32>,
+        max_dist: Option<f32>,
+    ) -> PyResult<(Py<PyArray1<i32>>, Py<PyArray1<f32>>)> {
+        let rtree = self.rtree.as_ref().ok_or_else(|| {
+            PyValueError::new_err(
+                "CHGraph was built without coordinates; pass xs/ys to ch_build_from_csr to enable snap()",
+            )
+        })?;
+        let xs = xs.as_slice()?;
+        let ys = ys.as_slice()?;
+        if xs.len() != ys.len() {
+            return Err(PyValueError::new_err("xs and ys must have the same length"));
+        }
+        let max_dist_sq = max_dist.map(|d| d * d);
+        let n = xs.len();
+        let mut ids = vec![-1i32; n];
+        let mut dist_sq = vec![f32::INFINITY; n];
+
+        py.allow_threads(|| {
+            ids.par_iter_mut()
+                .zip(dist_sq.par_iter_mut())
+                .enumerate()
+                .for_each(|(i, (id, ds))| {
+                    let query = [xs[i], ys[i]];
+                    if let Some(nearest) = rtree.nearest_neighbor(&query) {
+                        let d = nearest.distance_2(&query);
+                        if max_dist_sq.map_or(true, |m| d <= m) {
+                            *id = nearest.id as i32;
+                            *ds = d;
+                        }
+                    }
+                });
+        });
+
+        Ok((
+            PyArray1::from_vec_bound(py, ids).unbind(),
+            PyArray1::from_vec_bound(py, dist_sq).unbind(),
+        ))
+    }
+
+    #[pyo3(signature = (source, limit=None))]
+    fn query_all(&self, py: Python<'_>, source: usize, limit: Option<u32>) -> PyResult<Py<PyArray1<u32>>> {
+        let lim = limit.unwrap_or(u32::MAX);
+        let dist = self.run_phast(source, lim);
+        Ok(PyArray1::from_vec_bound(py, dist).unbind())
+    }
+
+    #[pyo3(signature = (source, targets, limit=None))]
+    fn query_subset(
+        &self,
+        py: Python<'_>,
+        source: usize,
+        targets: PyReadonlyArray1<i32>,
+        limit: Option<u32>,
+    ) -> PyResult<Py<PyArray1<u32>>> {
+        let lim = limit.unwrap_or(u32::MAX);
+        let dist = self.run_phast(source, lim);
+        let idx = targets.as_slice()?;
+        let mut out = Vec::with_capacity(idx.len());
+        for &raw in idx {
+            if raw < 0 {
+                out.push(INF_U32);
+            } else {
+                let j = raw as usize;
+                if j < dist.len() {
+                    out.push(dist[j]);
+                } else {
+                    out.push(INF_U32);
+                }
+            }
+        }
+        Ok(PyArray1::from_vec_bound(py, out).unbind())
+    }
+
+    /// Full `len(sources) x len(targets)` distance matrix, running PHAST once
+    /// per source in parallel across rayon workers (each worker gets its own
+    /// `dist` scratch buffer inside `run_phast`; the graph and down-CSR are
+    /// shared read-only). This is the core op for accessibility/isochrone
+    /// batch jobs that would otherwise loop `query_subset` serially.
+    #[pyo3(signature = (sources, targets, limit=None))]
+    fn query_matrix(
+        &self,
+        py: Python<'_>,
+        sources: PyReadonlyArray1<i32>,
+        targets: PyReadonlyArray1<i32>,
+        limit: Option<u32>,
+    ) -> PyResult<Py<PyArray2<u32>>> {
+        let lim = limit.unwrap_or(u32::MAX);
+        let sources = sources.as_slice()?.to_vec();
+        let targets = targets.as_slice()?.to_vec();
+        let n_sources = sources.len();
+        let n_targets = targets.len();
+
+        let mut out_vec = vec![INF_U32; n_sources * n_targets];
+        py.allow_threads(|| {
+            out_vec
+                .par_chunks_mut(n_targets)
+                .zip(sources.par_iter())
+                .for_each(|(row, &src_raw)| {
+                    if src_raw < 0 {
+                        return;
+                    }
+                    let src = src_raw as usize;
+                    if src >= self.node_count() {
+                        return;
+                    }
+                    let dist = self.run_phast(src, lim);
+                    for (cell, &tgt_raw) in row.iter_mut().zip(targets.iter()) {
+                        if tgt_raw < 0 {
+                            continue;
+                        }
+                        let tgt = tgt_raw as usize;
+                        if tgt < dist.len() {
+                            *cell = dist[tgt];
+                        }
+                    }
+                });
+        });
+
+        let arr = unsafe { PyArray2::new_bound(py, [n_sources, n_targets], false) };
+        let arr_slice = unsafe { arr.as_slice_mut()? };
+        arr_slice.copy_from_slice(&out_vec);
+        Ok(arr.into())
+    }
+
+    /// Point-to-point query via bidirectional CH search: an upward Dijkstra
+    /// from `source` over `edges_fwd`, and a mirrored upward Dijkstra from
+    /// `target` over `up_edges` (ascending the same way, but via `edges_bwd`),
+    /// meeting where `dist_fwd[v] + dist_bwd[v]` is minimized.
+    /// Returns `(INF_U32, [])` if `target` is unreachable from `source`
+    /// within `limit`. The returned path is the sequence of original node ids.
+    #[pyo3(signature = (source, target, limit=None))]
+    fn query_path(
+        &self,
+        py: Python<'_>,
+        source: usize,
+        target: usize,
+        limit: Option<u32>,
+    ) -> PyResult<(u32, Py<PyArray1<u32>>)> {
+        let n = self.node_count();
+        let lim = limit.unwrap_or(u32::MAX);
+        if source >= n || target >= n {
+            return Ok((INF_U32, PyArray1::from_vec_bound(py, Vec::new()).unbind()));
+        }
+
+        let (dist_fwd, pred_fwd) = self.run_upward_fwd_with_pred(source, lim);
+        let (dist_bwd, pred_bwd) = self.run_upward_bwd_with_pred(target, lim);
+
+        let mut best_cost = INF_U32;
+        let mut meet = usize::MAX;
+        for v in 0..n {
+            let df = dist_fwd[v];
+            let db = dist_bwd[v];
+            if df == INF_U32 || db == INF_U32 {
+                continue;
+            }
+            let total = df.saturating_add(db);
+            if total < best_cost {
+                best_cost = total;
+                meet = v;
+            }
+        }
+
+        if meet == usize::MAX || best_cost > lim {
+            return Ok((INF_U32, PyArray1::from_vec_bound(py, Vec::new()).unbind()));
+        }
+
+        // Walk source -> meet, unpacking each predecessor edge as we go, then
+        // reverse since we walked it back-to-front from the meeting node.
+        let mut path: Vec<u32> = vec![meet as u32];
+        let mut cur = meet;
+        while pred_fwd[cur] != u32::MAX {
+            let edge_idx = pred_fwd[cur] as usize;
+            let edge = &self.graph.edges_fwd[edge_idx];
+            let mut expanded = Vec::new();
+            Self::unpack_edge(&self.graph.edges_fwd, edge_idx, &mut expanded);
+            expanded.pop(); // drop adj_node, already on `path` as `cur`
+            for &node in expanded.iter().rev() {
+                path.push(node);
+            }
+            cur = edge.base_node as usize;
+            path.push(cur as u32);
+        }
+        path.reverse();
+        path.pop(); // meet is re-added by the backward walk below
+
+        // Walk meet -> target the same way, this time in the natural order.
+        // `pred_bwd[cur]` is an index into `graph.edges_bwd` whose `adj_node`
+        // is `cur` and whose `base_node` is the predecessor (the node one
+        // step closer to `target`), the mirror of the `pred_fwd` walk above.
+        let mut cur = meet;
+        while pred_bwd[cur] != u32::MAX {
+            let edge_idx = pred_bwd[cur] as usize;
+            let edge = &self.graph.edges_bwd[edge_idx];
+            let mut expanded = Vec::new();
+            Self::unpack_edge(&self.graph.edges_bwd, edge_idx, &mut expanded);
+            expanded.pop(); // drop adj_node (== cur), it's pushed just below
+            path.push(cur as u32);
+            for &node in expanded.iter().rev() {
+                path.push(node);
+            }
+            cur = edge.base_node as usize;
+        }
+        path.push(target as u32);
+
+        Ok((best_cost, PyArray1::from_vec_bound(py, path).unbind()))
+    }
+
+    fn debug_edges(&self, node: usize) -> PyResult<(usize, Vec<(usize, usize, u32)>, Vec<(usize, usize, u32)>)> {
+        if node >= self.node_count() {
+            return Err(PyValueError::new_err("node out of range"));
+        }
+        let rank = self.graph.ranks[node] as usize;
+        let fwd_start = self.graph.first_edge_ids_fwd[rank] as usize;
+        let fwd_end = self.graph.first_edge_ids_fwd[rank + 1] as usize;
+        let mut fwd = Vec::new();
+        for idx in fwd_start..fwd_end {
+            let edge = &self.graph.edges_fwd[idx];
+            fwd.push((edge.base_node as usize, edge.adj_node as usize, edge.weight as u32));
+        }
+        let bwd_start = self.down_offsets[node];
+        let bwd_end = self.down_offsets[node + 1];
+        let mut bwd = Vec::new();
+        for idx in bwd_start..bwd_end {
+            let (v, w) = self.down_edges[idx];
+            bwd.push((node, v, w));
+        }
+        Ok((rank, fwd, bwd))
+    }
+
+    /// Build a `RestrictedTargets` once for a stable target list, so repeated
+    /// `query_restricted` calls against it only sweep the nodes that can
+    /// actually affect one of the targets instead of the whole graph.
+    fn build_restricted_targets(&self, targets: PyReadonlyArray1<i32>) -> PyResult<RestrictedTargets> {
+        let target_ids = targets.as_slice()?.to_vec();
+        let n = self.node_count();
+
+        let mut in_set = vec![false; n];
+        let mut stack: Vec<usize> = Vec::new();
+        for &t in &target_ids {
+            if t < 0 {
+                continue;
+            }
+            let tu = t as usize;
+            if tu < n {
+                self.mark_upward_closure(tu, &mut in_set, &mut stack);
+            }
+        }
+
+        let mut restricted_nodes: Vec<u32> = (0..n as u32).filter(|&u| in_set[u as usize]).collect();
+        restricted_nodes.sort_unstable_by_key(|&u| Reverse(self.graph.ranks[u as usize]));
+
+        let mut sub_offsets = vec![0usize; restricted_nodes.len() + 1];
+        let mut sub_edges: Vec<(u32, u32)> = Vec::new();
+        for (slot, &node) in restricted_nodes.iter().enumerate() {
+            let u = node as usize;
+            let start = self.down_offsets[u];
+            let end = self.down_offsets[u + 1];
+            for idx in start..end {
+                let (v, w) = self.down_edges[idx];
+                if in_set[v] {
+                    sub_edges.push((v as u32, w));
+                }
+            }
+            sub_offsets[slot + 1] = sub_edges.len();
+        }
+
+        Ok(RestrictedTargets {
+            target_ids,
+            restricted_nodes,
+            sub_offsets,
+            sub_edges,
+        })
+    }
+
+    /// RPHAST query: the normal upward Dijkstra from `source`, followed by a
+    /// downward sweep that only visits `restricted`'s sub-CSR in rank order,
+    /// producing distances aligned to the target array `restricted` was built
+    /// from. An order-of-magnitude faster than `query_subset` when the same
+    /// small target set is queried repeatedly from many sources.
+    #[pyo3(signature = (source, restricted, limit=None))]
+    fn query_restricted(
+        &self,
+        py: Python<'_>,
+        source: usize,
+        restricted: &RestrictedTargets,
+        limit: Option<u32>,
+    ) -> PyResult<Py<PyArray1<u32>>> {
+        let lim = limit.unwrap_or(u32::MAX);
+        let mut dist = self.run_upward(source, lim);
+
+        for (slot, &node) in restricted.restricted_nodes.iter().enumerate() {
+            let u = node as usize;
+            let du = dist[u];
+            if du == INF_U32 {
+                continue;
+            }
+            let start = restricted.sub_offsets[slot];
+            let end = restricted.sub_offsets[slot + 1];
+            for idx in start..end {
+                let (v, w) = restricted.sub_edges[idx];
+                let nd = du.saturating_add(w);
+                if nd > lim {
+                    continue;
+                }
+                let vi = v as usize;
+                if nd < dist[vi] {
+                    dist[vi] = nd;
+                }
+            }
+        }
+
+        let out: Vec<u32> = restricted
+            .target_ids
+            .iter()
+            .map(|&t| {
+                if t < 0 {
+                    return INF_U32;
+                }
+                let ti = t as usize;
+                if ti < dist.len() {
+                    dist[ti]
+                } else {
+                    INF_U32
+                }
+            })
+            .collect();
+        Ok(PyArray1::from_vec_bound(py, out).unbind())
+    }
+
+    /// Single-source shortest time subject to a bound on a secondary,
+    /// independent cost (e.g. a monetary toll or an elevation-gain budget).
+    /// CH shortcuts fold away the secondary metric, so this runs a
+    /// multi-criteria label-setting Dijkstra over the uncontracted CSR
+    /// retained by `ch_build_from_csr(..., w_sec2=...)` instead of PHAST.
+    /// Much slower than `query_all`/`query_subset` — use only when the
+    /// secondary budget is actually constraining the query.
+    #[pyo3(signature = (source, secondary_budget, limit=None))]
+    fn query_all_constrained(
+        &self,
+        py: Python<'_>,
+        source: usize,
+        secondary_budget: u16,
+        limit: Option<u32>,
+    ) -> PyResult<Py<PyArray1<u32>>> {
+        if self.csr_indptr.is_empty() {
+            return Err(PyValueError::new_err(
+                "CHGraph was built without a secondary metric; pass w_sec2 to ch_build_from_csr to enable query_all_constrained()",
+            ));
+        }
+        let n = self.node_count();
+        let lim = limit.unwrap_or(u32::MAX);
+        let mut best_time = vec![INF_U32; n];
+        if source >= n {
+            return Ok(PyArray1::from_vec_bound(py, best_time).unbind());
+        }
+
+        // Per-node Pareto frontier of (time, secondary) labels: a label is
+        // kept only if no retained label dominates it on both criteria.
+        let mut labels: Vec<Vec<(u32, u16)>> = vec![Vec::new(); n];
+        let mut heap: BinaryHeap<Reverse<(u32, u16, usize)>> = BinaryHeap::new();
+        labels[source].push((0, 0));
+        heap.push(Reverse((0, 0, source)));
+
+        while let Some(Reverse((time, sec, u))) = heap.pop() {
+            if time > lim || sec > secondary_budget {
+                continue;
+            }
+            // Stale entry: this label was since dominated by a better one.
+            if !labels[u].iter().any(|&(t, s)| t == time && s == sec) {
+                continue;
+            }
+            if time < best_time[u] {
+                best_time[u] = time;
+            }
+
+            let start = self.csr_indptr[u] as usize;
+            let end = self.csr_indptr[u + 1] as usize;
+            for idx in start..end {
+                let v_raw = self.csr_indices[idx];
+                if v_raw < 0 {
+                    continue;
+                }
+                let v = v_raw as usize;
+                let nt = time.saturating_add(self.csr_w_primary[idx] as u32);
+                let ns = sec.saturating_add(self.csr_w_secondary[idx]);
+                if nt > lim || ns > secondary_budget {
+                    continue;
+                }
+
+                let v_labels = &mut labels[v];
+                if v_labels
+                    .iter()
+                    .any(|&(t, s)| t <= nt && s <= ns)
+                {
+                    continue;
+                }
+                v_labels.retain(|&(t, s)| !(nt <= t && ns <= s));
+                v_labels.push((nt, ns));
+                heap.push(Reverse((nt, ns, v)));
+            }
+        }
+
+        Ok(PyArray1::from_vec_bound(py, best_time).unbind())
+    }
+}
+
+fn build_input_graph(
+    indptr: &[i64],
+    indices: &[i32],
+    w_sec: &[u16],
+) -> Result<InputGraph, PyErr> {
+    if indptr.is_empty() {
+        return Err(PyValueError::new_err("indptr must be non-empty"));
+    }
+    if indices.len() != w_sec.len() {
+        return Err(PyValueError::new_err("indices and weights must match in length"));
+    }
+    let mut g = InputGraph::new();
+    let n = indptr.len() - 1;
+    for u in 0..n {
+        let lo = indptr[u] as usize;
+        let hi = indptr[u + 1] as usize;
+        if hi > indices.len() {
+            return Err(PyValueError::new_err("indptr out of bounds for indices"));
+        }
+        for idx in lo..hi {
+            let v_raw = indices[idx];
+            if v_raw < 0 {
+                continue;
+            }
+            let v = v_raw as usize;
+            let w = w_sec[idx].max(1) as usize;
+            g.add_edge(u, v, w);
+        }
+    }
+    g.freeze();
+    Ok(g)
+}
+
+#[pyfunction]
+#[pyo3(signature = (indptr, indices, w_sec, xs=None, ys=None, w_sec2=None))]
+pub fn ch_build_from_csr(
+    indptr: PyReadonlyArray1<i64>,
+    indices: PyReadonlyArray1<i32>,
+    w_sec: PyReadonlyArray1<u16>,
+    xs: Option<PyReadonlyArray1<f32>>,
+    ys: Option<PyReadonlyArray1<f32>>,
+    w_sec2: Option<PyReadonlyArray1<u16>>,
+) -> PyResult<CHGraph> {
+    let indptr_slice = indptr.as_slice()?;
+    let indices_slice = indices.as_slice()?;
+    let w_sec_slice = w_sec.as_slice()?;
+
+    let input = build_input_graph(indptr_slice, indices_slice, w_sec_slice)?;
+    let fast_graph = fast_paths::prepare(&input);
+    let fast32 = FastGraph32::new(&fast_graph);
+    let mut ch = CHGraph::from_fast_graph32(fast32);
+    if let (Some(xs), Some(ys)) = (xs, ys) {
+        ch.build_rtree(xs.as_slice()?, ys.as_slice()?)?;
+    }
+    // The secondary metric (when given) makes CH shortcuts invalid for
+    // `query_all_constrained`, so we keep the uncontracted CSR around too.
+    if let Some(w_sec2) = w_sec2 {
+        ch.attach_uncontracted_csr(
+            indptr_slice.to_vec(),
+            indices_slice.to_vec(),
+            w_sec_slice.to_vec(),
+            w_sec2.as_slice()?.to_vec(),
+        );
+    }
+    Ok(ch)
+}
+
+#[pyfunction]
+pub fn ch_from_bytes(data: &Bound<'_, PyBytes>) -> PyResult<CHGraph> {
+    let bytes = data.as_bytes();
+    match bincode::deserialize::<FastGraph32>(bytes) {
+        Ok(graph) => Ok(CHGraph::from_fast_graph32(graph)),
+        Err(e) => Err(PyValueError::new_err(format!("failed to deserialize CH graph: {e}"))),
+    }
+}
+
+/// Read a u64 LE length prefix at `*cursor` and advance past it.
+fn read_len(bytes: &[u8], cursor: &mut usize) -> PyResult<usize> {
+    let slice = bytes
+        .get(*cursor..*cursor + 8)
+        .ok_or_else(|| PyValueError::new_err("truncated CH graph file"))?;
+    *cursor += 8;
+    Ok(u64::from_le_bytes(slice.try_into().unwrap()) as usize)
+}
+
+/// Read a length-prefixed `u32` array into an owned `Vec`. When this offset
+/// happens to be 4-byte aligned, reinterprets the slice as `[u32]` to copy
+/// it out in one shot instead of decoding element-by-element; this only
+/// speeds up the copy out of the (possibly mmap'd) input bytes — the result
+/// is always a freshly allocated `Vec`, never a view borrowing from `bytes`.
+fn read_u32_vec(bytes: &[u8], cursor: &mut usize) -> PyResult<Vec<u32>> {
+    let len = read_len(bytes, cursor)?;
+    let byte_len = len * 4;
+    let slice = bytes
+        .get(*cursor..*cursor + byte_len)
+        .ok_or_else(|| PyValueError::new_err("truncated CH graph file"))?;
+    *cursor += byte_len;
+    // SAFETY: read-only reinterpretation of `slice` as `[u32]` to decode it
+    // faster than per-element `from_le_bytes`; we only take this path when
+    // `align_to` reports no unaligned prefix/suffix, and the result is
+    // `collect()`-ed into an owned `Vec` below, not retained as a borrow.
+    let (prefix, view, suffix) = unsafe { slice.align_to::<u32>() };
+    if prefix.is_empty() && suffix.is_empty() {
+        Ok(view.iter().map(|v| u32::from_le(*v)).collect())
+    } else {
+        Ok(slice.chunks_exact(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect())
+    }
+}
+
+fn read_usize_vec(bytes: &[u8], cursor: &mut usize) -> PyResult<Vec<usize>> {
+    let len = read_len(bytes, cursor)?;
+    let mut out = Vec::with_capacity(len);
+    for _ in 0..len {
+        let slice = bytes
+            .get(*cursor..*cursor + 8)
+            .ok_or_else(|| PyValueError::new_err("truncated CH graph file"))?;
+        out.push(u64::from_le_bytes(slice.try_into().unwrap()) as usize);
+        *cursor += 8;
+    }
+    Ok(out)
+}
+
+/// Read a length-prefixed `(node, weight)` pair array — the on-disk shape of
+/// both `down_edges` and `up_edges`.
+fn read_edge_pairs(bytes: &[u8], cursor: &mut usize) -> PyResult<Vec<(usize, u32)>> {
+    let len = read_len(bytes, cursor)?;
+    let mut out = Vec::with_capacity(len);
+    for _ in 0..len {
+        let slice = bytes
+            .get(*cursor..*cursor + 8)
+            .ok_or_else(|| PyValueError::new_err("truncated CH graph file"))?;
+        let base = u32::from_le_bytes(slice[0..4].try_into().unwrap()) as usize;
+        let weight = u32::from_le_bytes(slice[4..8].try_into().unwrap());
+        out.push((base, weight));
+        *cursor += 8;
+    }
+    Ok(out)
+}
+
+/// Memory-map a file written by `CHGraph.save` and reconstruct the CSR views
+/// without rebuilding `order`/`down_offsets`/`down_edges` from scratch — the
+/// mmap is only used to avoid a `read()` syscall and buffer for the whole
+/// file; every array below is still decoded into an owned `Vec` (the mmap
+/// itself is dropped when this function returns), not held zero-copy.
+/// Rejects files whose version this build doesn't understand, and files
+/// that fail the trailing SHA3-256 checksum.
+#[pyfunction]
+pub fn ch_load_mmap(path: &str) -> PyResult<CHGraph> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| PyValueError::new_err(format!("failed to open {}: {}", path, e)))?;
+    let mmap = unsafe { memmap2::Mmap::map(&file) }
+        .map_err(|e| PyValueError::new_err(format!("failed to mmap {}: {}", path, e)))?;
+    let bytes: &[u8] = &mmap;
+
+    if bytes.len() < CH_FILE_MAGIC.len() + 4 + CH_DIGEST_LEN || &bytes[..CH_FILE_MAGIC.len()] != CH_FILE_MAGIC {
+        return Err(PyValueError::new_err("not a CH graph file (bad magic)"));
+    }
+    let mut cursor = CH_FILE_MAGIC.len();
+    let version = u32::from_le_bytes(bytes[cursor..cursor + 4].try_into().unwrap());
+    cursor += 4;
+    if version != CH_FILE_VERSION {
+        return Err(PyValueError::new_err(format!(
+            "unsupported CH graph file version {} (this build understands version {})",
+            version, CH_FILE_VERSION
+        )));
+    }
+
+    let payload_len = bytes.len() - CH_DIGEST_LEN;
+    let expected_digest = CHGraph::sha3_digest(&bytes[..payload_len]);
+    if expected_digest != bytes[payload_len..] {
+        return Err(PyValueError::new_err(
+            "CH graph file failed checksum validation (corrupt or truncated)",
+        ));
+    }
+
+    let graph_len = read_len(bytes, &mut cursor)?;
+    let graph_bytes = bytes
+        .get(cursor..cursor + graph_len)
+        .ok_or_else(|| PyValueError::new_err("truncated CH graph file"))?;
+    cursor += graph_len;
+    let graph: FastGraph32 = bincode::deserialize(graph_bytes)
+        .map_err(|e| PyValueError::new_err(format!("failed to deserialize CH graph: {e}")))?;
+
+    let order = read_u32_vec(bytes, &mut cursor)?;
+    let down_offsets = read_usize_vec(bytes, &mut cursor)?;
+    let down_edges = read_edge_pairs(bytes, &mut cursor)?;
+    let down_edge_bwd_idx = read_u32_vec(bytes, &mut cursor)?;
+    let up_offsets = read_usize_vec(bytes, &mut cursor)?;
+    let up_edges = read_edge_pairs(bytes, &mut cursor)?;
+    let up_edge_bwd_idx = read_u32_vec(bytes, &mut cursor)?;
+
+    Ok(CHGraph {
+        graph,
+        order,
+        down_offsets,
+        down_edges,
+        down_edge_bwd_idx,
+        up_offsets,
+        up_edges,
+        up_edge_bwd_idx,
+        rtree: None,
+        csr_indptr: Vec::new(),
+        csr_indices: Vec::new(),
+        csr_w_primary: Vec::new(),
+        csr_w_secondary: Vec::new(),
+    })
+}
@@ -1,14 +1,15 @@
 mod ch;
 
-use numpy::{PyArray1, PyArray2, PyReadonlyArray1, PyArrayMethods};
+use numpy::{PyArray1, PyArray2, PyArray3, PyReadonlyArray1, PyArrayMethods};
 use pyo3::prelude::*;
 use std::collections::BinaryHeap;
-use std::cmp::Reverse;
 use std::cmp::min;
 use rustc_hash::FxHashMap;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use h3o::{CellIndex, LatLng, Resolution};
+use rstar::{PointDistance, RTree, RTreeObject, AABB};
+use std::sync::Mutex;
 
 const UNREACHABLE: u16 = 65535;
 
@@ -114,102 +115,676 @@ fn insert_label_for_node(
     }
 }
 
-#[pyfunction]
+/// A frontier/settled entry: accumulated time, current node, originating
+/// source, the source's label/category, and (when `return_paths` is
+/// requested) the predecessor node and the CSR edge slot used to reach
+/// this node from it (-1 for source seeds).
+type KbestEntry = (u16, i32, i32, i32, i32, i32);
+
+/// Push `entry` into its `(node, label)` cell's K-best max-heap if it's a
+/// candidate, on settle (no per-src dedup — a src may legitimately occupy more than one slot if it reaches
+/// a node at two different times before both are settled). Compares the
+/// *full* entry tuple, not just `.0` (time), so that entries tying on time
+/// are kept/evicted by a fixed order (time, node, src, label, ...) rather
+/// than whichever arrives first — the parallel relaxation order across
+/// rayon workers is otherwise nondeterministic, which would otherwise make
+/// the surviving tie-broken set vary run-to-run.
+#[inline]
+fn settle_kbest(heap: &Mutex<BinaryHeap<KbestEntry>>, entry: KbestEntry, k: usize) {
+    let mut h = heap.lock().unwrap();
+    if h.len() < k {
+        h.push(entry);
+    } else if let Some(mut worst) = h.peek_mut() {
+        if entry < *worst {
+            *worst = entry;
+        }
+    }
+}
+
+/// Pruning check run before pushing a relax candidate into a bucket: skip
+/// generating the request at all if the `(node, label)` cell already has K
+/// strictly-better times. Candidates merely *tying* the
+/// current worst time are still let through (`>` rather than `>=`) — the
+/// heap's current max time is a monotonically non-increasing upper bound
+/// on the eventual worst-of-K, but which entry holds that time can still
+/// change as more candidates arrive, so a tie has to reach `settle_kbest`'s
+/// full-tuple comparison to be resolved deterministically instead of being
+/// dropped based on arrival timing.
+#[inline]
+fn worth_relaxing_kbest(heap: &Mutex<BinaryHeap<KbestEntry>>, new_time: u16, k: usize) -> bool {
+    let h = heap.lock().unwrap();
+    !(h.len() == k && h.peek().map(|&(t, ..)| new_time > t).unwrap_or(false))
+}
+
+/// Truncate a frontier batch to the `beam_width` entries with the smallest
+/// tentative times (no-op when `beam_width == 0`, meaning unbounded/exact).
+/// Used by `kbest_multisource_csr`'s approximate mode: with a finite beam,
+/// entries outside it are dropped before they can be relaxed, so returned
+/// times become upper bounds rather than guaranteed optima.
+#[inline]
+fn apply_beam(frontier: &mut Vec<KbestEntry>, beam_width: usize) {
+    if beam_width > 0 && frontier.len() > beam_width {
+        frontier.select_nth_unstable_by_key(beam_width - 1, |&(t, ..)| t);
+        frontier.truncate(beam_width);
+    }
+}
+
+/// Multi-source K-best-time search over a CSR graph, parallelized via
+/// Δ-stepping: frontier entries `(time, node, src, label)` are bucketed by
+/// `time / delta`, the lowest non-empty bucket is relaxed across light
+/// edges (`weight <= delta`) in parallel rounds with rayon until it stops
+/// producing new entries for itself, then its heavy edges (`weight >
+/// delta`) are relaxed once. `source_labels[i]` is the category of
+/// `source_idxs[i]` (e.g. amenity type); K-best heaps are kept per
+/// `(node, label)` pair rather than per node, so domination is evaluated
+/// within a category — reaching the nearest pharmacy never evicts the
+/// nearest school from the same node's table. Heaps are behind a `Mutex`
+/// since multiple sources/threads can settle the same `(node, label)`
+/// concurrently.
+///
+/// `beam_width` (0 = unbounded/exact) caps each expansion batch to the
+/// `beam_width` smallest tentative times via `select_nth_unstable`,
+/// trading optimality for speed on continental-scale precomputes — with a
+/// finite beam, returned times are upper bounds, not guaranteed optima.
+///
+/// Output arrays are shaped `(n_nodes, n_labels, k)`. `return_paths`, when
+/// set, also returns predecessor-node and edge-index arrays of the same
+/// shape alongside `best_src`/`time_s`, one pair per K-best entry, so
+/// callers can walk parent pointers back to the source and reconstruct
+/// the actual route (e.g. for GeoJSON geometry). When unset, those two
+/// arrays are returned with shape `(0, 0, 0)` since they cost a copy to
+/// materialize.
+#[pyfunction(signature = (
+    indptr, indices, w_sec, source_idxs, source_labels, n_labels, k, cutoff_s, threads, delta_s=None, beam_width=0, return_paths=false
+))]
+#[allow(clippy::too_many_arguments)]
 fn kbest_multisource_csr(
     py: Python,
     indptr: PyReadonlyArray1<i64>,
     indices: PyReadonlyArray1<i32>,
     w_sec: PyReadonlyArray1<u16>,
     source_idxs: PyReadonlyArray1<i32>,
+    source_labels: PyReadonlyArray1<i32>,
+    n_labels: usize,
     k: usize,
     cutoff_s: u16,
-    _threads: usize, // Parameter is unused for now in this single-threaded version
-) -> PyResult<(Py<PyArray2<i32>>, Py<PyArray2<u16>>)> {
+    threads: usize,
+    delta_s: Option<u16>,
+    beam_width: usize,
+    return_paths: bool,
+) -> PyResult<(Py<PyArray3<i32>>, Py<PyArray3<u16>>, Py<PyArray3<i32>>, Py<PyArray3<i32>>)> {
     let indptr = indptr.as_slice()?;
     let indices = indices.as_slice()?;
     let w_sec = w_sec.as_slice()?;
     let source_idxs = source_idxs.as_slice()?;
+    let source_labels = source_labels.as_slice()?;
+    if source_labels.len() != source_idxs.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "source_labels must have the same length as source_idxs",
+        ));
+    }
+    if n_labels == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("n_labels must be >= 1"));
+    }
 
     let n_nodes = indptr.len() - 1;
+    let threads_n = if threads == 0 { 1 } else { threads };
 
-    // State for Dijkstra's algorithm
-    // A max-heap for each node to store the K best times.
-    // We store (time, source_node_index).
-    let mut best_results: Vec<BinaryHeap<(u16, i32)>> = vec![BinaryHeap::with_capacity(k); n_nodes];
-    
-    // The main priority queue for the search. Min-heap.
-    // We store Reverse((time, current_node_index, source_node_index)).
-    let mut pq: BinaryHeap<Reverse<(u16, i32, i32)>> = BinaryHeap::new();
-
-    // Initialize PQ with all source nodes
-    for &src_idx in source_idxs {
-        pq.push(Reverse((0, src_idx, src_idx)));
-    }
+    // Δ defaults to roughly the median edge weight: large enough that most
+    // edges are "light" (few repeat rounds per bucket), small enough that
+    // buckets stay narrow and expose parallelism across them.
+    let delta: u16 = match delta_s {
+        Some(d) if d > 0 => d,
+        _ => {
+            if w_sec.is_empty() {
+                1
+            } else {
+                let mut sorted = w_sec.to_vec();
+                sorted.sort_unstable();
+                sorted[sorted.len() / 2].max(1)
+            }
+        }
+    };
+    let n_buckets = (cutoff_s as usize / delta as usize) + 2;
 
-    while let Some(Reverse((time, u_idx, src_idx))) = pq.pop() {
-        if time > cutoff_s {
+    // One K-best heap per (node, label) pair, flattened as `node * n_labels + label`.
+    let best_results: Vec<Mutex<BinaryHeap<KbestEntry>>> =
+        (0..n_nodes * n_labels).map(|_| Mutex::new(BinaryHeap::with_capacity(k))).collect();
+    let buckets: Vec<Mutex<Vec<KbestEntry>>> =
+        (0..n_buckets).map(|_| Mutex::new(Vec::new())).collect();
+
+    for (&src_idx, &label) in source_idxs.iter().zip(source_labels.iter()) {
+        if src_idx < 0 || src_idx as usize >= n_nodes {
             continue;
         }
-
-        // Check if this path is a candidate for the K-best list for node u_idx
-        let u_bests = &mut best_results[u_idx as usize];
-        if u_bests.len() < k {
-            u_bests.push((time, src_idx));
-        } else {
-            // If the heap is full, check if the new time is better than the worst (max) time
-            if let Some(mut worst) = u_bests.peek_mut() {
-                if time < worst.0 {
-                    *worst = (time, src_idx);
-                } else {
-                    // This path is not better than any of the K best, but we still need to
-                    // relax its edges as a shorter path to other nodes might exist through it.
-                }
-            }
+        if label < 0 || label as usize >= n_labels {
+            return Err(pyo3::exceptions::PyValueError::new_err("source_labels entry out of range"));
         }
+        buckets[0].lock().unwrap().push((0, src_idx, src_idx, label, -1, -1));
+    }
 
-        // Relax edges
-        let start = indptr[u_idx as usize] as usize;
-        let end = indptr[(u_idx + 1) as usize] as usize;
-        for i in start..end {
-            let v_idx = indices[i];
-            let weight = w_sec[i];
-            let new_time = time + weight;
-
-            if new_time < cutoff_s {
-                 // Optimization: if we have K results for v_idx and the new time is not better than the worst, don't push to PQ.
-                let v_bests = &best_results[v_idx as usize];
-                if v_bests.len() == k && new_time >= v_bests.peek().unwrap().0 {
+    py.allow_threads(|| {
+        let pool = ThreadPoolBuilder::new().num_threads(threads_n).build().unwrap();
+        pool.install(|| {
+            let mut cur = 0usize;
+            while cur < n_buckets {
+                let mut frontier: Vec<KbestEntry> =
+                    std::mem::take(&mut *buckets[cur].lock().unwrap());
+                apply_beam(&mut frontier, beam_width);
+                if frontier.is_empty() {
+                    cur += 1;
                     continue;
                 }
-                pq.push(Reverse((new_time, v_idx, src_idx)));
+
+                // Everything settled while this bucket was the active one,
+                // across all inner rounds — the heavy-edge pass below runs
+                // over this set exactly once, after it stabilizes.
+                let mut all_settled: Vec<KbestEntry> = Vec::new();
+
+                while !frontier.is_empty() {
+                    for &entry in &frontier {
+                        let cell = entry.1 as usize * n_labels + entry.3 as usize;
+                        settle_kbest(&best_results[cell], entry, k);
+                    }
+                    all_settled.extend_from_slice(&frontier);
+
+                    let reinsert: Vec<KbestEntry> = frontier
+                        .par_iter()
+                        .flat_map(|&(time, u, src, label, _, _)| {
+                            let mut same_bucket = Vec::new();
+                            let start = indptr[u as usize] as usize;
+                            let end = indptr[(u as usize) + 1] as usize;
+                            for i in start..end {
+                                let w = w_sec[i];
+                                if w > delta {
+                                    continue; // heavy, deferred below
+                                }
+                                let v = indices[i];
+                                let nt = time.saturating_add(w);
+                                if nt >= cutoff_s {
+                                    continue;
+                                }
+                                let cell = v as usize * n_labels + label as usize;
+                                if !worth_relaxing_kbest(&best_results[cell], nt, k) {
+                                    continue;
+                                }
+                                let tb = (nt as usize) / (delta as usize);
+                                let next = (nt, v, src, label, u, i as i32);
+                                if tb == cur {
+                                    same_bucket.push(next);
+                                } else if tb < n_buckets {
+                                    buckets[tb].lock().unwrap().push(next);
+                                }
+                            }
+                            same_bucket
+                        })
+                        .collect();
+
+                    frontier = reinsert;
+                    apply_beam(&mut frontier, beam_width);
+                }
+
+                // Heavy edges only ever push forward past the current
+                // bucket (time + w > delta*(cur+1) whenever w > delta and
+                // time is already in [cur*delta, (cur+1)*delta)), so one
+                // relax pass per bucket suffices.
+                all_settled.par_iter().for_each(|&(time, u, src, label, _, _)| {
+                    let start = indptr[u as usize] as usize;
+                    let end = indptr[(u as usize) + 1] as usize;
+                    for i in start..end {
+                        let w = w_sec[i];
+                        if w <= delta {
+                            continue; // light, already relaxed above
+                        }
+                        let v = indices[i];
+                        let nt = time.saturating_add(w);
+                        if nt >= cutoff_s {
+                            continue;
+                        }
+                        let cell = v as usize * n_labels + label as usize;
+                        if !worth_relaxing_kbest(&best_results[cell], nt, k) {
+                            continue;
+                        }
+                        let tb = (nt as usize) / (delta as usize);
+                        if tb < n_buckets {
+                            buckets[tb].lock().unwrap().push((nt, v, src, label, u, i as i32));
+                        }
+                    }
+                });
+
+                cur += 1;
             }
-        }
-    }
+        });
+    });
 
     // Prepare output arrays
-    let (best_src_idx_out, time_s_out) = unsafe {
-        let best_src_idx_out = PyArray2::new_bound(py, [n_nodes, k], false);
-        let time_s_out = PyArray2::new_bound(py, [n_nodes, k], false);
-        (best_src_idx_out, time_s_out)
+    let (parent_rows, parent_labels, parent_cols) =
+        if return_paths { (n_nodes, n_labels, k) } else { (0, 0, 0) };
+    let (best_src_idx_out, time_s_out, parent_node_out, parent_edge_out) = unsafe {
+        let best_src_idx_out = PyArray3::new_bound(py, [n_nodes, n_labels, k], false);
+        let time_s_out = PyArray3::new_bound(py, [n_nodes, n_labels, k], false);
+        let parent_node_out = PyArray3::new_bound(py, [parent_rows, parent_labels, parent_cols], false);
+        let parent_edge_out = PyArray3::new_bound(py, [parent_rows, parent_labels, parent_cols], false);
+        (best_src_idx_out, time_s_out, parent_node_out, parent_edge_out)
     };
-    
+
     let best_src_idx_out_slice = unsafe { best_src_idx_out.as_slice_mut()? };
     let time_s_out_slice = unsafe { time_s_out.as_slice_mut()? };
+    let parent_node_out_slice = unsafe { parent_node_out.as_slice_mut()? };
+    let parent_edge_out_slice = unsafe { parent_edge_out.as_slice_mut()? };
 
     // Fill with sentinels
     best_src_idx_out_slice.fill(-1);
     time_s_out_slice.fill(UNREACHABLE);
+    parent_node_out_slice.fill(-1);
+    parent_edge_out_slice.fill(-1);
+
+    for (cell, heap) in best_results.into_iter().enumerate() {
+        let sorted_bests: Vec<KbestEntry> = heap.into_inner().unwrap().into_sorted_vec();
+        for (j, (time, _node, src_idx, _label, parent_node, parent_edge)) in sorted_bests.into_iter().enumerate() {
+            let idx = cell * k + j;
+            best_src_idx_out_slice[idx] = src_idx;
+            time_s_out_slice[idx] = time;
+            if return_paths {
+                parent_node_out_slice[idx] = parent_node;
+                parent_edge_out_slice[idx] = parent_edge;
+            }
+        }
+    }
+
+    Ok((best_src_idx_out.into(), time_s_out.into(), parent_node_out.into(), parent_edge_out.into()))
+}
+
+/// Priority queue key for `astar_csr`: `g + greediness * h`, ordered so a
+/// `BinaryHeap` pops the smallest value first (f32 has no `Ord`, and
+/// NaN can't arise here since every input is a finite distance/time).
+#[derive(Clone, Copy, PartialEq)]
+struct AstarPriority(f32);
+impl Eq for AstarPriority {}
+impl PartialOrd for AstarPriority {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for AstarPriority {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
 
-    for (i, heap) in best_results.into_iter().enumerate() {
-        let sorted_bests: Vec<(u16, i32)> = heap.into_sorted_vec();
-        for (j, (time, src_idx)) in sorted_bests.into_iter().enumerate() {
-             let idx = i * k + j;
-             best_src_idx_out_slice[idx] = src_idx;
-             time_s_out_slice[idx] = time;
+/// Admissible remaining-time estimate for weighted-A*: great-circle
+/// distance from `u` to the nearest target, divided by `max_speed_mps`.
+#[inline]
+fn astar_heuristic_s(u: usize, lat: &[f32], lon: &[f32], targets: &[usize], max_speed_mps: f32) -> f32 {
+    let (ulat, ulon) = (lat[u] as f64, lon[u] as f64);
+    let mut best = f64::INFINITY;
+    for &t in targets {
+        let d = haversine_dist_m(ulat, ulon, lat[t] as f64, lon[t] as f64);
+        if d < best {
+            best = d;
         }
     }
-    
-    Ok((best_src_idx_out.into(), time_s_out.into()))
+    (best / max_speed_mps as f64) as f32
+}
+
+/// Goal-directed weighted-A* query for point-to-point or one-to-few
+/// routing, a cheaper sibling to the full K-best multisource sweep. The
+/// priority of a popped node `u` is `g(u) + greediness * h(u)`, where `g`
+/// is the accumulated seconds and `h(u)` is `astar_heuristic_s` — an
+/// admissible lower bound at `greediness == 1.0` (exact A*); values above
+/// `1.0` trade optimality for speed. Search stops at the first target
+/// popped (ties among `targets` broken by queue order) and returns the
+/// reconstructed `src..=target` node path plus total time, or an empty
+/// path with `UNREACHABLE` if no target is reached within `cutoff_s`.
+#[pyfunction(signature = (
+    indptr, indices, w_sec, src, targets, lat, lon, greediness, cutoff_s, max_speed_mps=30.0
+))]
+fn astar_csr(
+    py: Python,
+    indptr: PyReadonlyArray1<i64>,
+    indices: PyReadonlyArray1<i32>,
+    w_sec: PyReadonlyArray1<u16>,
+    src: i32,
+    targets: PyReadonlyArray1<i32>,
+    lat: PyReadonlyArray1<f32>,
+    lon: PyReadonlyArray1<f32>,
+    greediness: f32,
+    cutoff_s: u16,
+    max_speed_mps: f32,
+) -> PyResult<(Py<PyArray1<i32>>, u16)> {
+    let indptr = indptr.as_slice()?;
+    let indices = indices.as_slice()?;
+    let w_sec = w_sec.as_slice()?;
+    let targets = targets.as_slice()?;
+    let lat = lat.as_slice()?;
+    let lon = lon.as_slice()?;
+    let n_nodes = indptr.len() - 1;
+
+    if greediness < 1.0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("greediness must be >= 1.0"));
+    }
+    if max_speed_mps <= 0.0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("max_speed_mps must be > 0"));
+    }
+    if src < 0 || src as usize >= n_nodes {
+        return Err(pyo3::exceptions::PyValueError::new_err("src out of range"));
+    }
+    if lat.len() != n_nodes || lon.len() != n_nodes {
+        return Err(pyo3::exceptions::PyValueError::new_err("lat/lon must have one entry per node"));
+    }
+    let target_set: Vec<usize> = targets
+        .iter()
+        .filter_map(|&t| if t >= 0 && (t as usize) < n_nodes { Some(t as usize) } else { None })
+        .collect();
+    if target_set.is_empty() {
+        return Err(pyo3::exceptions::PyValueError::new_err("targets must contain at least one valid node"));
+    }
+
+    let src = src as usize;
+    let (path, total_time) = py.allow_threads(|| {
+        let mut g_score: Vec<u32> = vec![u32::MAX; n_nodes];
+        let mut pred: Vec<i32> = vec![-1; n_nodes];
+        let mut visited: Vec<bool> = vec![false; n_nodes];
+        let mut open: BinaryHeap<(AstarPriority, usize)> = BinaryHeap::new();
+
+        g_score[src] = 0;
+        let h0 = astar_heuristic_s(src, lat, lon, &target_set, max_speed_mps);
+        open.push((AstarPriority(greediness * h0), src));
+
+        let mut reached: Option<usize> = None;
+        while let Some((_, u)) = open.pop() {
+            if visited[u] {
+                continue;
+            }
+            visited[u] = true;
+            if target_set.contains(&u) {
+                reached = Some(u);
+                break;
+            }
+
+            let gu = g_score[u];
+            let start = indptr[u] as usize;
+            let end = indptr[u + 1] as usize;
+            for i in start..end {
+                let v = indices[i] as usize;
+                let ng = gu.saturating_add(w_sec[i] as u32);
+                if ng > cutoff_s as u32 || ng >= g_score[v] {
+                    continue;
+                }
+                g_score[v] = ng;
+                pred[v] = u as i32;
+                let h = astar_heuristic_s(v, lat, lon, &target_set, max_speed_mps);
+                open.push((AstarPriority(ng as f32 + greediness * h), v));
+            }
+        }
+
+        match reached {
+            None => (Vec::new(), UNREACHABLE),
+            Some(t) => {
+                let mut path = vec![t as i32];
+                let mut cur = t;
+                while cur != src {
+                    cur = pred[cur] as usize;
+                    path.push(cur as i32);
+                }
+                path.reverse();
+                (path, g_score[t] as u16)
+            }
+        }
+    });
+
+    Ok((PyArray1::from_vec_bound(py, path).unbind(), total_time))
+}
+
+/// Largest `must_visit` size for which `best_visit_order` runs the exact
+/// Held-Karp DP. Above this, it falls back to nearest-neighbor + 2-opt,
+/// since Held-Karp's `O(2^n * n^2)` table blows up past a few more stops.
+const HELD_KARP_MAX_STOPS: usize = 15;
+
+/// Sentinel standing in for an infeasible transition during DP/2-opt
+/// accumulation — large enough that one `UNREACHABLE` leg can never look
+/// cheaper than any feasible tour, but small enough to add a few times
+/// without overflowing `u32`.
+const INFEASIBLE: u32 = u32::MAX / 4;
+
+/// Optimal visiting order for a small set of mandatory stops given a
+/// dense `(m, m)` travel-time matrix (seconds, `UNREACHABLE` = 65535 for
+/// missing pairs) — e.g. one row/column per stop from a multisource scan
+/// run from each selected location. `start` is the matrix row/column the
+/// tour begins at; `must_visit` is every other stop that must appear in
+/// the order (may be empty). With `return_to_start`, the tour closes back
+/// to `start` and the reported total time includes the closing leg.
+///
+/// For `must_visit.len() <= HELD_KARP_MAX_STOPS`, solves exactly via
+/// Held-Karp: `dp[S][j]` is the minimal time of a path from `start`
+/// visiting exactly the stops in bitmask `S` and ending at stop `j`,
+/// with transition `dp[S|{v}][v] = min over j in S of dp[S][j] +
+/// time[j][v]`. Larger instances fall back to a nearest-neighbor
+/// construction refined by 2-opt, which is fast but not guaranteed
+/// optimal. Returns an error if every candidate order is blocked by an
+/// `UNREACHABLE` leg.
+#[pyfunction(signature = (time_matrix, start, must_visit, return_to_start))]
+fn best_visit_order(
+    py: Python,
+    time_matrix: numpy::PyReadonlyArray2<u16>,
+    start: i32,
+    must_visit: PyReadonlyArray1<i32>,
+    return_to_start: bool,
+) -> PyResult<(Py<PyArray1<i32>>, u16)> {
+    let mat = time_matrix.as_array();
+    let m = mat.shape()[0];
+    if mat.shape()[1] != m {
+        return Err(pyo3::exceptions::PyValueError::new_err("time_matrix must be square"));
+    }
+    if start < 0 || start as usize >= m {
+        return Err(pyo3::exceptions::PyValueError::new_err("start out of range"));
+    }
+    let start = start as usize;
+    let stops = must_visit.as_slice()?;
+    let mut seen = vec![false; m];
+    seen[start] = true;
+    let mut stops_vec: Vec<usize> = Vec::with_capacity(stops.len());
+    for &s in stops {
+        if s < 0 || s as usize >= m {
+            return Err(pyo3::exceptions::PyValueError::new_err("must_visit index out of range"));
+        }
+        let s = s as usize;
+        if s == start || seen[s] {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "must_visit must not repeat start or another stop",
+            ));
+        }
+        seen[s] = true;
+        stops_vec.push(s);
+    }
+    let n = stops_vec.len();
+
+    let cost = |from: usize, to: usize| -> u32 {
+        let t = mat[[from, to]];
+        if t == UNREACHABLE { INFEASIBLE } else { t as u32 }
+    };
+
+    if n == 0 {
+        let mut order = vec![start as i32];
+        let mut total = 0u32;
+        if return_to_start {
+            total = total.saturating_add(cost(start, start));
+            order.push(start as i32);
+        }
+        if total >= INFEASIBLE {
+            return Err(pyo3::exceptions::PyValueError::new_err("no feasible visiting order exists"));
+        }
+        return Ok((PyArray1::from_vec_bound(py, order).unbind(), total_as_u16(total)));
+    }
+
+    let (order_stops, total) = if n <= HELD_KARP_MAX_STOPS {
+        held_karp_order(&stops_vec, start, return_to_start, &cost)
+    } else {
+        nearest_neighbor_2opt_order(&stops_vec, start, return_to_start, &cost)
+    };
+
+    if total >= INFEASIBLE {
+        return Err(pyo3::exceptions::PyValueError::new_err("no feasible visiting order exists"));
+    }
+
+    let mut order: Vec<i32> = Vec::with_capacity(order_stops.len() + 2);
+    order.push(start as i32);
+    order.extend(order_stops.iter().map(|&s| s as i32));
+    if return_to_start {
+        order.push(start as i32);
+    }
+
+    Ok((PyArray1::from_vec_bound(py, order).unbind(), total_as_u16(total)))
+}
+
+/// Saturate a `u32` tour total to `u16`, landing on `UNREACHABLE` instead of
+/// silently wrapping for totals past 65534s — easily reached by chaining a
+/// dozen or so matrix legs that are each individually representable.
+fn total_as_u16(total: u32) -> u16 {
+    if total >= UNREACHABLE as u32 {
+        UNREACHABLE
+    } else {
+        total as u16
+    }
+}
+
+/// Exact Held-Karp DP over `stops` (indices into the travel-time matrix,
+/// not `0..stops.len()`). Returns the visiting order (a permutation of
+/// `stops`) and its total time from `start`, closing back to `start` when
+/// `return_to_start` is set.
+fn held_karp_order(
+    stops: &[usize],
+    start: usize,
+    return_to_start: bool,
+    cost: &dyn Fn(usize, usize) -> u32,
+) -> (Vec<usize>, u32) {
+    let n = stops.len();
+    let n_masks = 1usize << n;
+    // dp[mask][j]: minimal time of a path from `start` visiting exactly
+    // the stops in `mask`, ending at stop index `j` (into `stops`).
+    let mut dp = vec![INFEASIBLE; n_masks * n];
+    let mut parent = vec![usize::MAX; n_masks * n];
+
+    for j in 0..n {
+        let mask = 1usize << j;
+        dp[mask * n + j] = cost(start, stops[j]);
+    }
+
+    for mask in 1..n_masks {
+        for j in 0..n {
+            if mask & (1 << j) == 0 {
+                continue;
+            }
+            let cur = dp[mask * n + j];
+            if cur >= INFEASIBLE {
+                continue;
+            }
+            for v in 0..n {
+                if mask & (1 << v) != 0 {
+                    continue;
+                }
+                let next_mask = mask | (1 << v);
+                let candidate = cur.saturating_add(cost(stops[j], stops[v]));
+                if candidate < dp[next_mask * n + v] {
+                    dp[next_mask * n + v] = candidate;
+                    parent[next_mask * n + v] = j;
+                }
+            }
+        }
+    }
+
+    let full = n_masks - 1;
+    let mut best_j = 0usize;
+    let mut best_total = INFEASIBLE;
+    for j in 0..n {
+        let mut total = dp[full * n + j];
+        if total >= INFEASIBLE {
+            continue;
+        }
+        if return_to_start {
+            total = total.saturating_add(cost(stops[j], start));
+        }
+        if total < best_total {
+            best_total = total;
+            best_j = j;
+        }
+    }
+
+    if best_total >= INFEASIBLE {
+        return (Vec::new(), INFEASIBLE);
+    }
+
+    let mut order_idx = vec![0usize; n];
+    let mut mask = full;
+    let mut j = best_j;
+    for slot in (0..n).rev() {
+        order_idx[slot] = j;
+        let prev = parent[mask * n + j];
+        mask &= !(1 << j);
+        j = prev;
+    }
+
+    (order_idx.into_iter().map(|idx| stops[idx]).collect(), best_total)
+}
+
+/// Nearest-neighbor construction plus 2-opt local search for instances
+/// too large for exact Held-Karp. Not guaranteed optimal, but fast and
+/// good enough once `stops` grows past a handful of locations.
+fn nearest_neighbor_2opt_order(
+    stops: &[usize],
+    start: usize,
+    return_to_start: bool,
+    cost: &dyn Fn(usize, usize) -> u32,
+) -> (Vec<usize>, u32) {
+    let n = stops.len();
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut order_idx: Vec<usize> = Vec::with_capacity(n);
+    let mut cur = start;
+    while !remaining.is_empty() {
+        let (pos, &next) = remaining
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &idx)| cost(cur, stops[idx]))
+            .unwrap();
+        order_idx.push(next);
+        cur = stops[next];
+        remaining.remove(pos);
+    }
+
+    let tour_cost = |order: &[usize]| -> u32 {
+        let mut total = 0u32;
+        let mut prev = start;
+        for &idx in order {
+            total = total.saturating_add(cost(prev, stops[idx]));
+            prev = stops[idx];
+        }
+        if return_to_start {
+            total = total.saturating_add(cost(prev, start));
+        }
+        total
+    };
+
+    let mut best_cost = tour_cost(&order_idx);
+    loop {
+        let mut improved = false;
+        for i in 0..n.saturating_sub(1) {
+            for j in (i + 1)..n {
+                let mut candidate = order_idx.clone();
+                candidate[i..=j].reverse();
+                let candidate_cost = tour_cost(&candidate);
+                if candidate_cost < best_cost {
+                    order_idx = candidate;
+                    best_cost = candidate_cost;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    (order_idx.into_iter().map(|idx| stops[idx]).collect(), best_cost)
 }
 
 /// Bucket-based multi-source single-label SSSP (Dial's algorithm) composed into K-pass to build K-best labels.
@@ -587,6 +1162,110 @@ fn weakly_connected_components(
     Ok(arr.into())
 }
 
+/// Below this k, the per-insert linear-scan Vec is cheaper than the
+/// heap+dedup-map machinery, so `TopK` only switches to the heap variant
+/// once k exceeds this threshold.
+const TOPK_HEAP_THRESHOLD: usize = 8;
+
+/// Per-hex top-K accumulator shared by `aggregate_h3_topk` and
+/// `aggregate_h3_topk_precached`. For small k, a linear-scan Vec (as
+/// before) is fastest; for large k, a bounded max-heap keyed on `time_s`
+/// with an `FxHashMap<site_id, time>` for dedup avoids re-sorting on every
+/// insertion. `heap` may carry stale `(time, site)` entries for sites
+/// whose time later improved — `seen` is the source of truth, and stale
+/// entries are discarded lazily on pop (see `drain_sorted`).
+enum TopK {
+    Small(Vec<(i32, u16)>),
+    Heap(BinaryHeap<(u16, i32)>, FxHashMap<i32, u16>),
+}
+
+impl TopK {
+    fn new(k: usize) -> Self {
+        if k <= TOPK_HEAP_THRESHOLD {
+            TopK::Small(Vec::with_capacity(k))
+        } else {
+            TopK::Heap(BinaryHeap::with_capacity(k), FxHashMap::default())
+        }
+    }
+}
+
+#[inline]
+fn update_topk(top: &mut TopK, site: i32, ts: u16, k: usize) {
+    match top {
+        TopK::Small(vec) => {
+            for p in vec.iter_mut() {
+                if p.0 == site {
+                    if ts < p.1 { p.1 = ts; }
+                    return;
+                }
+            }
+            if vec.len() < k {
+                vec.push((site, ts));
+                return;
+            }
+            let mut worst_i = 0usize;
+            let mut worst_t = 0u16;
+            for (i, &(_, t)) in vec.iter().enumerate() {
+                if i == 0 || t > worst_t { worst_i = i; worst_t = t; }
+            }
+            if ts < worst_t {
+                vec[worst_i] = (site, ts);
+            }
+        }
+        TopK::Heap(heap, seen) => {
+            if let Some(&old) = seen.get(&site) {
+                if old <= ts { return; }
+                seen.insert(site, ts);
+                heap.push((ts, site));
+                return;
+            }
+            if seen.len() < k {
+                seen.insert(site, ts);
+                heap.push((ts, site));
+                return;
+            }
+            // Lazily drop stale root entries (ones `seen` has since improved)
+            // until the true current worst is on top, *before* reading it —
+            // otherwise a stale, larger-than-true root lets a `ts` that's
+            // actually worse than the real worst slip past the reject check.
+            while let Some(&(t, s)) = heap.peek() {
+                if seen.get(&s) == Some(&t) { break; }
+                heap.pop();
+            }
+            let Some(&(root_t, _)) = heap.peek() else { return; };
+            if ts >= root_t {
+                return;
+            }
+            if let Some((t, s)) = heap.pop() {
+                if seen.get(&s) == Some(&t) {
+                    seen.remove(&s);
+                }
+            }
+            seen.insert(site, ts);
+            heap.push((ts, site));
+        }
+    }
+}
+
+/// Drain a `TopK` into its final `(site, time)` pairs sorted ascending by
+/// `(time, site)` — the order every caller emits in.
+fn drain_topk_sorted(top: TopK) -> Vec<(i32, u16)> {
+    let mut out: Vec<(i32, u16)> = match top {
+        TopK::Small(vec) => vec,
+        TopK::Heap(heap, seen) => heap
+            .into_iter()
+            .filter(|&(t, s)| seen.get(&s) == Some(&t))
+            .map(|(t, s)| (s, t))
+            .collect(),
+    };
+    out.sort_unstable_by(|x, y| {
+        let o = x.1.cmp(&y.1);
+        if o != std::cmp::Ordering::Equal { return o; }
+        x.0.cmp(&y.0)
+    });
+    out
+}
+
 /// Aggregate node-level labels (best anchors and times) into H3 hex buckets with per-hex top-K reduction.
 /// Inputs:
 /// - lats, lons: node coordinates (deg), length N
@@ -653,50 +1332,8 @@ fn aggregate_h3_topk(
     }
 
     // Each part returns Vec<HashMap<h3_id, TopK(site_id,time)>> of length R
-    type TopK = Vec<(i32, u16)>; // kept small (<=k)
     type HexMap = FxHashMap<u64, TopK>;
 
-    #[inline]
-    fn update_topk(vec: &mut TopK, site: i32, ts: u16, k: usize) {
-        use std::cmp::Ordering;
-        // If site exists, keep its minimum time
-        for p in vec.iter_mut() {
-            if p.0 == site {
-                if ts < p.1 { p.1 = ts; }
-                // Re-establish order after improvement
-                vec.sort_unstable_by(|x, y| {
-                    let o = x.1.cmp(&y.1);
-                    if o != Ordering::Equal { return o; }
-                    x.0.cmp(&y.0)
-                });
-                return;
-            }
-        }
-        if vec.len() < k {
-            vec.push((site, ts));
-            vec.sort_unstable_by(|x, y| {
-                let o = x.1.cmp(&y.1);
-                if o != Ordering::Equal { return o; }
-                x.0.cmp(&y.0)
-            });
-            return;
-        }
-        // vec full; replace worst if better
-        let mut worst_i = 0usize;
-        let mut worst_t = 0u16;
-        for (i, &(_, t)) in vec.iter().enumerate() {
-            if i == 0 || t > worst_t { worst_i = i; worst_t = t; }
-        }
-        if ts < worst_t {
-            vec[worst_i] = (site, ts);
-            vec.sort_unstable_by(|x, y| {
-                let o = x.1.cmp(&y.1);
-                if o != Ordering::Equal { return o; }
-                x.0.cmp(&y.0)
-            });
-        }
-    }
-
     let pool = ThreadPoolBuilder::new().num_threads(threads_n).build()
         .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to build thread pool: {}", e)))?;
 
@@ -713,7 +1350,7 @@ fn aggregate_h3_topk(
                     let cell = ll.to_cell(res);
                     let h3_id: u64 = cell.into();
                     let map_for_res = local.get_mut(ri).unwrap();
-                    let entry = map_for_res.entry(h3_id).or_insert_with(|| Vec::with_capacity(k));
+                    let entry = map_for_res.entry(h3_id).or_insert_with(|| TopK::new(k));
                     // iterate labels for this node; keep per-hex top-K online
                     for j in 0..kdim {
                         let site = a[[i, j]];
@@ -734,8 +1371,8 @@ fn aggregate_h3_topk(
         for (ri, hm) in pr.into_iter().enumerate() {
             let g = globals.get_mut(ri).unwrap();
             for (h3_id, inner) in hm.into_iter() {
-                let gin = g.entry(h3_id).or_insert_with(|| Vec::with_capacity(k));
-                for (site, ts) in inner.into_iter() {
+                let gin = g.entry(h3_id).or_insert_with(|| TopK::new(k));
+                for (site, ts) in drain_topk_sorted(inner) {
                     update_topk(gin, site, ts, k);
                 }
             }
@@ -748,22 +1385,17 @@ fn aggregate_h3_topk(
     let mut out_t: Vec<u16> = Vec::new();
     let mut out_r: Vec<i32> = Vec::new();
 
-    for (ri, g) in globals.iter().enumerate() {
+    for (ri, g) in globals.into_iter().enumerate() {
         let r_val: i32 = res_list[ri];
-        for (h3_id, pairs) in g.iter() {
+        for (h3_id, top) in g.into_iter() {
             // Ensure sorted (time asc, then site) and emit all (<=K)
-            let mut tmp = pairs.clone();
-            tmp.sort_unstable_by(|x, y| {
-                let o = x.1.cmp(&y.1);
-                if o != std::cmp::Ordering::Equal { return o; }
-                x.0.cmp(&y.0)
-            });
+            let tmp = drain_topk_sorted(top);
             out_h.reserve(tmp.len());
             out_s.reserve(tmp.len());
             out_t.reserve(tmp.len());
             out_r.reserve(tmp.len());
             for (site, ts) in tmp.into_iter() {
-                out_h.push(*h3_id);
+                out_h.push(h3_id);
                 out_s.push(site);
                 out_t.push(ts);
                 out_r.push(r_val);
@@ -917,7 +1549,6 @@ fn aggregate_h3_topk_precached(
     Py<PyArray1<u16>>, // time_s
     Py<PyArray1<i32>>, // res
 )> {
-    type TopK = Vec<(i32, u16)>; // (site, time)
     type HexMap = FxHashMap<u64, TopK>;
 
     let h3_arr = h3_ids.as_array();
@@ -960,26 +1591,13 @@ fn aggregate_h3_topk_precached(
                         let h3_id = h3_arr[[i, ri]];
                         if h3_id == 0 { continue; }
                         let map_for_res = local.get_mut(ri).unwrap();
-                        let entry = map_for_res.entry(h3_id).or_insert_with(|| Vec::with_capacity(k));
+                        let entry = map_for_res.entry(h3_id).or_insert_with(|| TopK::new(k));
                         for j in 0..kdim {
                             let site = a[[i, j]];
                             if site < 0 { continue; }
                             let ts = t_arr[[i, j]];
                             if ts >= unreachable { continue; }
-                            // update_topk inline
-                            let mut found = false;
-                            for p in entry.iter_mut() {
-                                if p.0 == site { if ts < p.1 { p.1 = ts; } found = true; break; }
-                            }
-                            if !found {
-                                if entry.len() < k { entry.push((site, ts)); }
-                                else {
-                                    // replace worst if better
-                                    let mut worst_i = 0usize; let mut worst_t = 0u16;
-                                    for (ii, &(_, t)) in entry.iter().enumerate() { if ii==0 || t>worst_t { worst_i=ii; worst_t=t; } }
-                                    if ts < worst_t { entry[worst_i] = (site, ts); }
-                                }
-                            }
+                            update_topk(entry, site, ts, k);
                         }
                     }
                 }
@@ -997,21 +1615,9 @@ fn aggregate_h3_topk_precached(
             for (ri, hm) in pr.into_iter().enumerate() {
                 let g = globals.get_mut(ri).unwrap();
                 for (h3_id, inner) in hm.into_iter() {
-                    let gin = g.entry(h3_id).or_insert_with(|| Vec::with_capacity(k));
-                    for (site, ts) in inner.into_iter() {
-                        // same update_topk
-                        let mut found = false;
-                        for p in gin.iter_mut() {
-                            if p.0 == site { if ts < p.1 { p.1 = ts; } found = true; break; }
-                        }
-                        if !found {
-                            if gin.len() < k { gin.push((site, ts)); }
-                            else {
-                                let mut worst_i = 0usize; let mut worst_t = 0u16;
-                                for (ii, &(_, t)) in gin.iter().enumerate() { if ii==0 || t>worst_t { worst_i=ii; worst_t=t; } }
-                                if ts < worst_t { gin[worst_i] = (site, ts); }
-                            }
-                        }
+                    let gin = g.entry(h3_id).or_insert_with(|| TopK::new(k));
+                    for (site, ts) in drain_topk_sorted(inner) {
+                        update_topk(gin, site, ts, k);
                     }
                 }
             }
@@ -1022,18 +1628,14 @@ fn aggregate_h3_topk_precached(
         let mut out_s: Vec<i32> = Vec::new();
         let mut out_t: Vec<u16> = Vec::new();
         let mut out_r: Vec<i32> = Vec::new();
-        for (ri, g) in globals.iter().enumerate() {
+        for (ri, g) in globals.into_iter().enumerate() {
             let r_val: i32 = res_list[ri];
-            for (h3_id, pairs) in g.iter() {
+            for (h3_id, top) in g.into_iter() {
                 // sort deterministically by (time, site)
-                let mut tmp = pairs.clone();
-                tmp.sort_unstable_by(|x, y| {
-                    let o = x.1.cmp(&y.1); if o != std::cmp::Ordering::Equal { return o; }
-                    x.0.cmp(&y.0)
-                });
+                let tmp = drain_topk_sorted(top);
                 out_h.reserve(tmp.len()); out_s.reserve(tmp.len()); out_t.reserve(tmp.len()); out_r.reserve(tmp.len());
                 for (site, ts) in tmp.into_iter() {
-                    out_h.push(*h3_id); out_s.push(site); out_t.push(ts); out_r.push(r_val);
+                    out_h.push(h3_id); out_s.push(site); out_t.push(ts); out_r.push(r_val);
                 }
             }
         }
@@ -1096,25 +1698,43 @@ fn build_csr_from_arrays(
             if oneway[i] == 0 { src.push(sv); dst.push(su); wt.push(wv); }
         }
 
-        // Sort by (src, dst)
+        // Bucket edges by src via counting sort in O(m + n): src is a dense
+        // node index in [0, n_nodes), so a tally + prefix-sum + scatter pass
+        // replaces the old O(m log m) comparison sort over (src, dst).
         let m = src.len();
-        let mut order: Vec<usize> = (0..m).collect();
-        order.sort_unstable_by(|&i, &j| {
-            let a = (src[i], dst[i]);
-            let b = (src[j], dst[j]);
-            a.cmp(&b)
-        });
-        let mut src_s: Vec<i32> = Vec::with_capacity(m);
-        let mut dst_s: Vec<i32> = Vec::with_capacity(m);
-        let mut w_s: Vec<u16> = Vec::with_capacity(m);
-        for &idx in &order { src_s.push(src[idx]); dst_s.push(dst[idx]); w_s.push(wt[idx]); }
-
-        // Build indptr via counts
         let mut counts: Vec<i64> = vec![0; n_nodes];
-        for &s in &src_s { counts[s as usize] += 1; }
+        for &s in &src { counts[s as usize] += 1; }
         let mut indptr: Vec<i64> = vec![0; n_nodes + 1];
         for i in 0..n_nodes { indptr[i + 1] = indptr[i] + counts[i]; }
 
+        let mut cursor: Vec<i64> = indptr[..n_nodes].to_vec();
+        let mut dst_s: Vec<i32> = vec![0; m];
+        let mut w_s: Vec<u16> = vec![0; m];
+        for i in 0..m {
+            let s = src[i] as usize;
+            let pos = cursor[s] as usize;
+            dst_s[pos] = dst[i];
+            w_s[pos] = wt[i];
+            cursor[s] += 1;
+        }
+
+        // Re-establish the old deterministic (src, dst) ordering within
+        // each (small-degree) bucket.
+        for i in 0..n_nodes {
+            let lo = indptr[i] as usize;
+            let hi = indptr[i + 1] as usize;
+            if hi - lo > 1 {
+                let bucket = &mut dst_s[lo..hi];
+                let w_bucket = &mut w_s[lo..hi];
+                let mut order: Vec<usize> = (0..bucket.len()).collect();
+                order.sort_unstable_by_key(|&j| bucket[j]);
+                let sorted_dst: Vec<i32> = order.iter().map(|&j| bucket[j]).collect();
+                let sorted_w: Vec<u16> = order.iter().map(|&j| w_bucket[j]).collect();
+                bucket.copy_from_slice(&sorted_dst);
+                w_bucket.copy_from_slice(&sorted_w);
+            }
+        }
+
         (indptr, dst_s, w_s)
     });
 
@@ -1128,17 +1748,203 @@ fn build_csr_from_arrays(
     Ok((node_ids_arr.into(), indptr_arr.into(), indices_arr.into(), w_arr.into(), lats_arr.into(), lons_arr.into()))
 }
 
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// A node in `NodeKdTree`: its graph index plus an equirectangular
+/// projection (about `ref_lat_cos`) used for the spatial index itself,
+/// with the original lat/lon kept alongside so the reported distance can
+/// use the exact metric the caller asked for.
+#[derive(Clone, Copy)]
+struct GeoNode {
+    node: u32,
+    lat: f64,
+    lon: f64,
+    proj_x: f32,
+    proj_y: f32,
+}
+
+impl RTreeObject for GeoNode {
+    type Envelope = AABB<[f32; 2]>;
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point([self.proj_x, self.proj_y])
+    }
+}
+
+impl PointDistance for GeoNode {
+    fn distance_2(&self, point: &[f32; 2]) -> f32 {
+        let dx = self.proj_x - point[0];
+        let dy = self.proj_y - point[1];
+        dx * dx + dy * dy
+    }
+}
+
+fn equirect_dist_m(lat0: f64, lon0: f64, lat1: f64, lon1: f64, ref_lat_cos: f64) -> f64 {
+    let dlat = (lat1 - lat0).to_radians();
+    let dlon = (lon1 - lon0).to_radians() * ref_lat_cos;
+    EARTH_RADIUS_M * (dlat * dlat + dlon * dlon).sqrt()
+}
+
+fn haversine_dist_m(lat0: f64, lon0: f64, lat1: f64, lon1: f64) -> f64 {
+    let phi1 = lat0.to_radians();
+    let phi2 = lat1.to_radians();
+    let dphi = (lat1 - lat0).to_radians();
+    let dlambda = (lon1 - lon0).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+    EARTH_RADIUS_M * c
+}
+
+/// Opaque KD-tree (backed by an R-tree, same as `CHGraph::snap`) over a
+/// node set's lat/lon, built once via `build_node_kdtree` and queried
+/// (possibly many times, in parallel) via `query_nearest_nodes`. Closes the
+/// gap between raw coordinates and the integer node space the rest of the
+/// crate operates on, so callers can snap POIs/origins onto the graph
+/// before handing node indices to `kbest_multisource_*`.
+#[pyclass(module = "t_hex")]
+pub struct NodeKdTree {
+    tree: RTree<GeoNode>,
+    ref_lat_cos: f64,
+}
+
+#[pymethods]
+impl NodeKdTree {
+    #[getter]
+    fn num_nodes(&self) -> usize {
+        self.tree.size()
+    }
+}
+
+/// Build a `NodeKdTree` over a node set's lat/lon arrays (as produced by
+/// `build_csr_from_arrays`). The tree is indexed on an equirectangular
+/// projection about the node set's mean latitude for fast queries;
+/// `query_nearest_nodes` reports exact distances in the metric requested.
+#[pyfunction]
+fn build_node_kdtree(
+    lats: PyReadonlyArray1<f32>,
+    lons: PyReadonlyArray1<f32>,
+) -> PyResult<NodeKdTree> {
+    let lats = lats.as_slice()?;
+    let lons = lons.as_slice()?;
+    if lats.len() != lons.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err("lats and lons must have the same length"));
+    }
+    let n = lats.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("lats/lons must be non-empty"));
+    }
+    let ref_lat_cos = (lats.iter().map(|&v| v as f64).sum::<f64>() / n as f64)
+        .to_radians()
+        .cos();
+    let points: Vec<GeoNode> = (0..n)
+        .map(|i| {
+            let lat = lats[i] as f64;
+            let lon = lons[i] as f64;
+            GeoNode {
+                node: i as u32,
+                lat,
+                lon,
+                proj_x: (lon.to_radians() * ref_lat_cos) as f32,
+                proj_y: lat.to_radians() as f32,
+            }
+        })
+        .collect();
+    Ok(NodeKdTree { tree: RTree::bulk_load(points), ref_lat_cos })
+}
+
+/// Batched k-nearest-node lookup against a `NodeKdTree`. `metric` selects
+/// how the reported distances (meters) are computed: "equirectangular"
+/// (fast, fine for city-scale queries) or "haversine" (exact great-circle).
+/// Runs across `threads` with the GIL released, like the other batch ops
+/// in this crate. Output is `(n_queries, k)` node indices (-1 padding when
+/// fewer than `k` nodes exist) and matching distances (`f32::INFINITY`
+/// padding).
+#[pyfunction]
+#[pyo3(signature = (tree, query_lats, query_lons, k, metric, threads))]
+fn query_nearest_nodes(
+    py: Python,
+    tree: &NodeKdTree,
+    query_lats: PyReadonlyArray1<f32>,
+    query_lons: PyReadonlyArray1<f32>,
+    k: usize,
+    metric: &str,
+    threads: usize,
+) -> PyResult<(Py<PyArray2<i32>>, Py<PyArray2<f32>>)> {
+    let query_lats = query_lats.as_slice()?;
+    let query_lons = query_lons.as_slice()?;
+    if query_lats.len() != query_lons.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err("query_lats and query_lons must have the same length"));
+    }
+    if k == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("k must be >= 1"));
+    }
+    let use_haversine = match metric {
+        "equirectangular" => false,
+        "haversine" => true,
+        other => {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "unknown metric '{other}'; expected 'equirectangular' or 'haversine'"
+            )))
+        }
+    };
+    let n_queries = query_lats.len();
+    let threads_n = if threads == 0 { 1 } else { threads };
+    let ref_lat_cos = tree.ref_lat_cos;
+    let rtree = &tree.tree;
+
+    let mut idx_out = vec![-1i32; n_queries * k];
+    let mut dist_out = vec![f32::INFINITY; n_queries * k];
+
+    py.allow_threads(|| {
+        let pool = ThreadPoolBuilder::new().num_threads(threads_n).build().unwrap();
+        pool.install(|| {
+            idx_out
+                .par_chunks_mut(k)
+                .zip(dist_out.par_chunks_mut(k))
+                .enumerate()
+                .for_each(|(i, (idx_row, dist_row))| {
+                    let lat = query_lats[i] as f64;
+                    let lon = query_lons[i] as f64;
+                    let proj_x = (lon.to_radians() * ref_lat_cos) as f32;
+                    let proj_y = lat.to_radians() as f32;
+                    let neighbors = rtree.nearest_neighbor_iter(&[proj_x, proj_y]).take(k);
+                    for (slot, node) in neighbors.enumerate() {
+                        let d = if use_haversine {
+                            haversine_dist_m(lat, lon, node.lat, node.lon)
+                        } else {
+                            equirect_dist_m(lat, lon, node.lat, node.lon, ref_lat_cos)
+                        };
+                        idx_row[slot] = node.node as i32;
+                        dist_row[slot] = d as f32;
+                    }
+                });
+        });
+    });
+
+    let idx_arr = unsafe { PyArray2::new_bound(py, [n_queries, k], false) };
+    let dist_arr = unsafe { PyArray2::new_bound(py, [n_queries, k], false) };
+    unsafe { idx_arr.as_slice_mut()? }.copy_from_slice(&idx_out);
+    unsafe { dist_arr.as_slice_mut()? }.copy_from_slice(&dist_out);
+    Ok((idx_arr.into(), dist_arr.into()))
+}
+
 #[pymodule]
 fn t_hex(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<ch::CHGraph>()?;
+    m.add_class::<ch::RestrictedTargets>()?;
+    m.add_class::<NodeKdTree>()?;
     m.add_function(wrap_pyfunction!(kbest_multisource_csr, m)?)?;
+    m.add_function(wrap_pyfunction!(astar_csr, m)?)?;
+    m.add_function(wrap_pyfunction!(best_visit_order, m)?)?;
     m.add_function(wrap_pyfunction!(kbest_multisource_bucket_csr, m)?)?;
     m.add_function(wrap_pyfunction!(aggregate_h3_topk, m)?)?;
     m.add_function(wrap_pyfunction!(aggregate_h3_topk_precached, m)?)?;
     m.add_function(wrap_pyfunction!(compute_h3_for_nodes, m)?)?;
     m.add_function(wrap_pyfunction!(weakly_connected_components, m)?)?;
     m.add_function(wrap_pyfunction!(build_csr_from_arrays, m)?)?;
+    m.add_function(wrap_pyfunction!(build_node_kdtree, m)?)?;
+    m.add_function(wrap_pyfunction!(query_nearest_nodes, m)?)?;
     m.add_function(wrap_pyfunction!(ch::ch_build_from_csr, m)?)?;
     m.add_function(wrap_pyfunction!(ch::ch_from_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(ch::ch_load_mmap, m)?)?;
     Ok(())
 }